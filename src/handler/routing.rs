@@ -1,5 +1,6 @@
 //!Routing related traits and types.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::iter::{Iterator, FlatMap};
 use std::slice::Split;
@@ -31,6 +32,23 @@ pub trait Route<'a> {
     ///assert_eq!(segments, expected);
     ///```
     fn segments(&'a self) -> <Self as Route<'a>>::Segments;
+
+    ///Create a percent-decoding route segment iterator: the same
+    ///segments as `segments()`, but with escape sequences like `%2F`
+    ///turned into their literal byte instead of being read as a
+    ///separator. Segments are decoded lazily, one at a time, and only
+    ///allocate when a segment actually contains a `%`.
+    ///
+    ///```rust
+    ///# use rustful::handler::routing::Route;
+    ///let path = "/share%2Ffile";
+    ///let segments = path.decoded_segments().collect::<Vec<_>>();
+    ///assert_eq!(segments.len(), 1);
+    ///assert_eq!(&*segments[0], &b"share/file"[..]);
+    ///```
+    fn decoded_segments(&'a self) -> PercentDecode<<Self as Route<'a>>::Segments> {
+        PercentDecode { iter: self.segments() }
+    }
 }
 
 fn is_slash(c: &u8) -> bool {
@@ -117,7 +135,7 @@ impl<I: Iterator> Iterator for RouteIter<I> {
 ///A state object for routing.
 #[derive(Clone)]
 pub struct RouteState<'a> {
-    route: Vec<&'a [u8]>,
+    route: Vec<Cow<'a, [u8]>>,
     variables: Vec<Option<usize>>,
     index: usize,
     var_index: usize,
@@ -125,7 +143,7 @@ pub struct RouteState<'a> {
 
 impl<'a> RouteState<'a> {
     ///Get the current path segment.
-    pub fn get(&self) -> Option<&'a [u8]> {
+    pub fn get(&self) -> Option<Cow<'a, [u8]>> {
         self.route.get(self.index).cloned()
     }
 
@@ -144,9 +162,19 @@ impl<'a> RouteState<'a> {
     }
 
     ///Extend a previously saved variable value with this path segment, or
-    ///save it as a new variable.
+    ///save it as a new variable. Continuing a variable started by the
+    ///immediately preceding `keep` or `fuse` reuses that variable's
+    ///index, rather than handing out a fresh one, so the two segments
+    ///are joined back together by `variables()`.
     pub fn fuse(&mut self) {
-        let v_i = self.var_index;
+        let v_i = match self.index.checked_sub(1).and_then(|i| self.variables.get(i)) {
+            Some(&Some(prev)) => prev,
+            _ => {
+                let v_i = self.var_index;
+                self.var_index += 1;
+                v_i
+            }
+        };
         self.variables.get_mut(self.index).map(|v| *v = Some(v_i));
         self.index += 1;
     }
@@ -155,20 +183,92 @@ impl<'a> RouteState<'a> {
     pub fn variables(&self, names: &[MaybeUtf8Owned]) -> HashMap<MaybeUtf8Owned, MaybeUtf8Owned> {
         let values = self.route.iter().zip(self.variables.iter()).filter_map(|(v, keep)| {
             if let Some(index) = *keep {
-                Some((index, *v))
+                Some((index, &**v))
             } else {
                 None
             }
         });
 
         let mut var_map = HashMap::<MaybeUtf8Owned, MaybeUtf8Owned>::with_capacity(names.len());
-        for (name, value) in VariableIter::new(names, values) {
+        for (_, name, value) in VariableIter::new(names, values) {
             var_map.insert(name, value);
         }
 
         var_map
     }
 
+    ///Don't include this path segment in a variable, unless it can be
+    ///parsed as `ty`. The current segment is consumed and recorded as a
+    ///variable only when parsing succeeds; on failure the state is left
+    ///untouched and `false` is returned, so a matcher can reject this
+    ///route and try another one.
+    ///
+    ///```rust
+    ///# use rustful::handler::routing::{RouteState, SegmentType};
+    ///let mut state = RouteState::from("/user/not_a_number");
+    ///state.skip(); //"user" is a literal
+    ///
+    /////the "id" route only accepts a numeric segment, so it's rejected...
+    ///assert_eq!(state.keep_typed(SegmentType::U64), false);
+    ///
+    /////...leaving the state untouched, so another route can try the
+    /////same segment as plain text instead
+    ///assert_eq!(state.keep_typed(SegmentType::Str), true);
+    ///```
+    pub fn keep_typed(&mut self, ty: SegmentType) -> bool {
+        match self.get() {
+            Some(ref segment) if ty.matches(segment) => {
+                self.keep();
+                true
+            },
+            _ => false
+        }
+    }
+
+    ///Assign names and types to the saved variables and return them as
+    ///parsed values. `types` is indexed the same way as `names`: one
+    ///entry per distinct variable, including fused (multi-segment)
+    ///captures. Returns `None` if any variable fails to parse as its
+    ///assigned type.
+    ///
+    ///```rust
+    ///# use rustful::context::MaybeUtf8Owned;
+    ///# use rustful::handler::routing::{RouteState, SegmentType, Variable};
+    ///fn name(s: &str) -> MaybeUtf8Owned {
+    ///    s.as_bytes().to_owned().into()
+    ///}
+    ///
+    ///let mut state = RouteState::from("/files/a/b");
+    ///state.skip(); //"files" is a literal
+    ///state.keep(); //"a" starts the "path" variable
+    ///state.fuse(); //"b" is fused onto "path", joining it back to "a/b"
+    ///
+    ///let names = vec![name("path")];
+    ///let types = vec![SegmentType::Str];
+    ///let values = state.typed_variables(&names, &types).expect("a valid match");
+    ///assert_eq!(values.get(&name("path")), Some(&Variable::Str(b"a/b".to_vec().into())));
+    ///```
+    pub fn typed_variables(&self, names: &[MaybeUtf8Owned], types: &[SegmentType]) -> Option<HashMap<MaybeUtf8Owned, Variable>> {
+        let values = self.route.iter().zip(self.variables.iter()).filter_map(|(v, keep)| {
+            if let Some(index) = *keep {
+                Some((index, &**v))
+            } else {
+                None
+            }
+        });
+
+        let mut var_map = HashMap::<MaybeUtf8Owned, Variable>::with_capacity(names.len());
+        for (index, name, value) in VariableIter::new(names, values) {
+            let ty = types.get(index).cloned().unwrap_or(SegmentType::Str);
+            match ty.parse(value.as_bytes()) {
+                Some(value) => { var_map.insert(name, value); },
+                None => return None
+            }
+        }
+
+        Some(var_map)
+    }
+
     ///Get a snapshot of a part of the current state.
     pub fn snapshot(&self) -> (usize, usize) {
         (self.index, self.var_index)
@@ -185,11 +285,61 @@ impl<'a> RouteState<'a> {
     pub fn is_empty(&self) -> bool {
         self.index == self.route.len()
     }
+
+    ///Create a route state from a normalizing route mode: each segment
+    ///is percent-decoded (so `%2F` becomes a literal byte, rather than
+    ///being read as a separator), empty interior segments (from `//`)
+    ///are dropped, `.` is skipped and `..` pops the previous segment
+    ///without being allowed to pop above the root. Unlike the raw,
+    ///unnormalized route built by `RouteState::from`, the result has no
+    ///empty, `.` or `..` segments. A decoded segment can still contain a
+    ///`/` byte (from `%2F`), so `..%2F..` stays the single segment `../..`.
+    ///
+    ///```rust
+    ///# use rustful::handler::routing::RouteState;
+    /////an embedded `%2F` stays part of the segment it's in, instead of
+    /////being read as an extra path separator
+    ///let state = RouteState::from_normalized("/share%2Ffile");
+    ///assert_eq!(state.get().as_ref().map(|s| &**s), Some(&b"share/file"[..]));
+    ///
+    /////an incomplete escape at the end of a segment is left as-is
+    ///let state = RouteState::from_normalized("/100%");
+    ///assert_eq!(state.get().as_ref().map(|s| &**s), Some(&b"100%"[..]));
+    ///
+    /////`//` doesn't produce an empty interior segment, and `.` segments
+    /////are dropped entirely
+    ///let mut state = RouteState::from_normalized("/a//./b");
+    ///state.keep();
+    ///assert_eq!(state.get().as_ref().map(|s| &**s), Some(&b"b"[..]));
+    ///
+    /////`..` pops the previous segment, whether written literally or
+    /////percent-encoded, but can't pop above the root
+    ///let mut state = RouteState::from_normalized("/a/%2e%2e/../escape");
+    ///assert_eq!(state.get().as_ref().map(|s| &**s), Some(&b"escape"[..]));
+    ///```
+    pub fn from_normalized<R: Route<'a> + ?Sized>(route: &'a R) -> RouteState<'a> {
+        let mut segments: Vec<Cow<'a, [u8]>> = Vec::new();
+
+        for segment in route.decoded_segments() {
+            match &*segment {
+                b"." | b"" => {},
+                b".." => { segments.pop(); },
+                _ => segments.push(segment)
+            }
+        }
+
+        RouteState {
+            variables: vec![None; segments.len()],
+            route: segments,
+            index: 0,
+            var_index: 0,
+        }
+    }
 }
 
 impl<'a, R: Route<'a> + ?Sized> From<&'a R> for RouteState<'a> {
     fn from(route: &'a R) -> RouteState<'a> {
-        let route: Vec<_> = route.segments().collect();
+        let route: Vec<_> = route.segments().map(Cow::Borrowed).collect();
         RouteState {
             variables: vec![None; route.len()],
             route: route,
@@ -199,6 +349,74 @@ impl<'a, R: Route<'a> + ?Sized> From<&'a R> for RouteState<'a> {
     }
 }
 
+///An iterator adapter, produced by `Route::decoded_segments`, that
+///percent-decodes each path segment coming from a `Route::Segments`
+///iterator, yielding owned, decoded bytes where decoding changed the
+///segment and borrowing straight through otherwise.
+#[derive(Clone)]
+pub struct PercentDecode<I> {
+    iter: I
+}
+
+impl<'a, I: Iterator<Item=&'a [u8]>> Iterator for PercentDecode<I> {
+    type Item = Cow<'a, [u8]>;
+
+    fn next(&mut self) -> Option<Cow<'a, [u8]>> {
+        self.iter.next().map(percent_decode)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+///Percent-decode a single path segment. Segments without a `%` are
+///returned unchanged, without allocating.
+fn percent_decode(segment: &[u8]) -> Cow<[u8]> {
+    if !segment.contains(&b'%') {
+        return Cow::Borrowed(segment);
+    }
+
+    let mut decoded = Vec::with_capacity(segment.len());
+    let mut i = 0;
+
+    while i < segment.len() {
+        let byte = segment[i];
+
+        if byte == b'%' && i + 2 < segment.len() {
+            if let (Some(hi), Some(lo)) = (from_hex(segment[i + 1]), from_hex(segment[i + 2])) {
+                decoded.push((hi << 4) | lo);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(byte);
+        i += 1;
+    }
+
+    //every decoded escape shortens the segment, so an equal length
+    //means nothing was decoded
+    if decoded.len() == segment.len() {
+        return Cow::Borrowed(segment);
+    }
+
+    Cow::Owned(decoded)
+}
+
+///Parse a single hexadecimal digit.
+fn from_hex(byte: u8) -> Option<u8> {
+    if byte >= b'0' && byte <= b'9' {
+        Some(byte - b'0')
+    } else if byte >= b'a' && byte <= b'f' {
+        Some(byte - b'a' + 10)
+    } else if byte >= b'A' && byte <= b'F' {
+        Some(byte - b'A' + 10)
+    } else {
+        None
+    }
+}
+
 struct VariableIter<'a, I> {
     iter: I,
     names: &'a [MaybeUtf8Owned],
@@ -216,7 +434,7 @@ impl<'a, I: Iterator<Item=(usize, &'a [u8])>> VariableIter<'a, I> {
 }
 
 impl<'a, I: Iterator<Item=(usize, &'a [u8])>> Iterator for VariableIter<'a, I> {
-    type Item=(MaybeUtf8Owned, MaybeUtf8Owned);
+    type Item=(usize, MaybeUtf8Owned, MaybeUtf8Owned);
 
     fn next(&mut self) -> Option<Self::Item> {
         for (next_index, next_segment) in &mut self.iter {
@@ -237,7 +455,7 @@ impl<'a, I: Iterator<Item=(usize, &'a [u8])>> Iterator for VariableIter<'a, I> {
                 } else {
                     //the current sequence has ended
                     self.current = Some((next_index, (*next_name).clone(), next_segment.to_owned().into()));
-                    return Some((name, segment));
+                    return Some((index, name, segment));
                 }
             } else {
                 //this is the first named variable
@@ -246,6 +464,401 @@ impl<'a, I: Iterator<Item=(usize, &'a [u8])>> Iterator for VariableIter<'a, I> {
         }
 
         //return the last variable
-        self.current.take().map(|(_, name, segment)| (name, segment))
+        self.current.take().map(|(index, name, segment)| (index, name, segment))
+    }
+}
+
+///The type a captured path segment is expected to parse as, analogous to
+///the type-checked captures used by some URL routers to let a route
+///pattern like `/user/:id` constrain `id` to an integer and reject the
+///match (falling through to the next route) otherwise.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SegmentType {
+    ///Accept the segment as is, without parsing it.
+    Str,
+    ///Parse the segment as an unsigned 64-bit integer.
+    U64,
+    ///Parse the segment as a signed 64-bit integer.
+    I64,
+    ///Parse the segment as a boolean (`true` or `false`).
+    Bool,
+}
+
+impl SegmentType {
+    ///Check if `segment` can be parsed as this type, without allocating.
+    fn matches(&self, segment: &[u8]) -> bool {
+        match *self {
+            SegmentType::Str => true,
+            SegmentType::U64 => ::std::str::from_utf8(segment).ok().and_then(|s| s.parse::<u64>().ok()).is_some(),
+            SegmentType::I64 => ::std::str::from_utf8(segment).ok().and_then(|s| s.parse::<i64>().ok()).is_some(),
+            SegmentType::Bool => ::std::str::from_utf8(segment).ok().and_then(|s| s.parse::<bool>().ok()).is_some(),
+        }
+    }
+
+    ///Parse `segment` into a `Variable` of this type.
+    fn parse(&self, segment: &[u8]) -> Option<Variable> {
+        match *self {
+            SegmentType::Str => Some(Variable::Str(segment.to_owned().into())),
+            SegmentType::U64 => ::std::str::from_utf8(segment).ok().and_then(|s| s.parse::<u64>().ok()).map(Variable::U64),
+            SegmentType::I64 => ::std::str::from_utf8(segment).ok().and_then(|s| s.parse::<i64>().ok()).map(Variable::I64),
+            SegmentType::Bool => ::std::str::from_utf8(segment).ok().and_then(|s| s.parse::<bool>().ok()).map(Variable::Bool),
+        }
+    }
+}
+
+///A path segment value, parsed as the `SegmentType` that was given for
+///it to `RouteState::typed_variables`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Variable {
+    ///A plain, unparsed value.
+    Str(MaybeUtf8Owned),
+    ///An unsigned 64-bit integer value.
+    U64(u64),
+    ///A signed 64-bit integer value.
+    I64(i64),
+    ///A boolean value.
+    Bool(bool),
+}
+
+///A single token in a route pattern, describing how `build_path` should
+///reconstruct one path segment. This is the inverse of the `skip`/`keep`/
+///`fuse` calls a matcher makes while walking a `RouteState`: the same
+///named variables, in the same order, turn back into a path.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteToken {
+    ///A literal path segment.
+    Literal(MaybeUtf8Owned),
+    ///The first (or only) segment of a named variable, as captured by
+    ///`RouteState::keep`.
+    Variable(MaybeUtf8Owned),
+    ///A continuation of the same-named variable onto another segment,
+    ///as captured by `RouteState::fuse`.
+    Fuse(MaybeUtf8Owned),
+}
+
+///Reconstruct a path from an ordered route pattern and a set of
+///variable values, the inverse of splitting a path into segments and
+///collecting its `variables()`. A variable that's only ever captured by
+///a single `RouteToken::Variable` has any `/` (and `%`) bytes in its
+///value percent-encoded, so they can't be misread as segment
+///separators; a variable that's fused by at least one `RouteToken::Fuse`
+///instead has its value re-split on `/` boundaries and written back out
+///verbatim, however many parts that turns out to be - a greedy fuse
+///match isn't bound by how many `Fuse` tokens the pattern happens to
+///have - since those slashes are the segment boundaries that `fuse`
+///joined in the first place.
+///
+///```rust
+///# use std::collections::HashMap;
+///# use rustful::handler::routing::{RouteState, RouteToken, build_path};
+///# use rustful::context::MaybeUtf8Owned;
+///fn name(s: &str) -> MaybeUtf8Owned {
+///    s.as_bytes().to_owned().into()
+///}
+///
+///let pattern = vec![
+///    RouteToken::Literal(name("user")),
+///    RouteToken::Variable(name("id")),
+///    RouteToken::Literal(name("files")),
+///    RouteToken::Variable(name("path")),
+///    RouteToken::Fuse(name("path")),
+///];
+///let names = vec![name("id"), name("path")];
+///
+/////forward: split "/user/42/files/a/b" into named variables
+///let mut state = RouteState::from("/user/42/files/a/b");
+///state.skip(); //"user" is a literal
+///state.keep();  //"42" is the "id" variable
+///state.skip(); //"files" is a literal
+///state.keep();  //"a" starts the "path" variable
+///state.fuse();  //"b" is fused onto "path"
+///let values = state.variables(&names);
+///assert_eq!(values.get(&name("path")).map(|v| v.as_bytes()), Some(&b"a/b"[..]));
+///
+/////reverse: rebuild the path from the pattern and those same values
+///let rebuilt = build_path(&pattern, &values);
+///assert_eq!(rebuilt.as_bytes(), b"/user/42/files/a/b");
+///
+/////a fused variable isn't limited to as many parts as the pattern has
+/////Fuse tokens for it - a greedy match can absorb more segments than that
+///let mut values = HashMap::new();
+///values.insert(name("path"), name("a/b/c"));
+///let rebuilt = build_path(&pattern[2..], &values);
+///assert_eq!(rebuilt.as_bytes(), b"/files/a/b/c");
+///```
+pub fn build_path(pattern: &[RouteToken], values: &HashMap<MaybeUtf8Owned, MaybeUtf8Owned>) -> MaybeUtf8Owned {
+    use std::collections::VecDeque;
+
+    //A variable is fused if it has at least one `Fuse` token; the
+    //number of `Fuse` tokens in the *pattern* doesn't bound how many
+    //`/`-separated parts a greedy fuse match actually captured, so all
+    //of a fused variable's parts are emitted at once, at its first
+    //`Variable` or `Fuse` token, rather than one part per token.
+    let mut fused = HashMap::<&MaybeUtf8Owned, bool>::new();
+    for token in pattern {
+        match *token {
+            RouteToken::Fuse(ref name) => { fused.insert(name, true); },
+            RouteToken::Variable(ref name) => { fused.entry(name).or_insert(false); },
+            RouteToken::Literal(_) => {}
+        }
+    }
+
+    let mut parts = HashMap::<&MaybeUtf8Owned, VecDeque<Vec<u8>>>::new();
+    for (&name, &is_fused) in &fused {
+        if let Some(value) = values.get(name) {
+            let segments = if is_fused {
+                value.as_bytes().split(|&b| b == b'/').map(|s| s.to_owned()).collect()
+            } else {
+                let mut segments = VecDeque::with_capacity(1);
+                segments.push_back(value.as_bytes().to_owned());
+                segments
+            };
+            parts.insert(name, segments);
+        }
+    }
+
+    let mut path = MaybeUtf8Owned::from(Vec::<u8>::new());
+
+    for token in pattern {
+        let name = match *token {
+            RouteToken::Literal(ref segment) => {
+                path.push_char('/');
+                path.push_bytes(segment.as_bytes());
+                continue;
+            },
+            //a later token of an already emitted variable finds its queue empty
+            RouteToken::Variable(ref name) | RouteToken::Fuse(ref name) => name
+        };
+
+        let is_fused = fused.get(name).cloned().unwrap_or(false);
+        if let Some(queue) = parts.get_mut(name) {
+            while let Some(part) = queue.pop_front() {
+                path.push_char('/');
+
+                if is_fused {
+                    path.push_bytes(&part);
+                } else {
+                    push_percent_encoded(&mut path, &part);
+                }
+            }
+        }
+    }
+
+    path
+}
+
+///Append `bytes` to `dest`, percent-encoding any byte that would
+///otherwise be read as a path separator or an escape sequence.
+fn push_percent_encoded(dest: &mut MaybeUtf8Owned, bytes: &[u8]) {
+    for &byte in bytes {
+        if byte == b'/' || byte == b'%' {
+            dest.push_char('%');
+            dest.push_bytes(&[to_hex(byte >> 4), to_hex(byte & 0xf)]);
+        } else {
+            dest.push_bytes(&[byte]);
+        }
+    }
+}
+
+///Turn a nibble into its uppercase hexadecimal digit.
+fn to_hex(nibble: u8) -> u8 {
+    if nibble < 10 {
+        b'0' + nibble
+    } else {
+        b'A' + nibble - 10
+    }
+}
+
+///A radix tree of route patterns, keyed segment by segment, so matching
+///a request path descends by its segments instead of scanning a flat
+///list of routes. A match found without backtracking costs
+///O(segments-in-path); a failed branch that is backed out of, or a
+///`fuse` capture backing off one segment at a time, adds to that.
+///
+///Each node holds its literal children (keyed by exact segment bytes),
+///at most one single-segment capturing child (`keep`) and at most one
+///greedy, multi-segment capturing child (`fuse`). `find` descends the
+///tree using the same `RouteState` primitives a hand-written matcher
+///would use, taking a `snapshot()` before trying a capturing child and
+///`go_to`-ing back to it if that branch doesn't lead to a match, so a
+///literal child is always preferred over a capture when both could
+///match.
+pub struct RouteTree<T> {
+    literal: HashMap<Vec<u8>, RouteTree<T>>,
+    keep: Option<Box<RouteTree<T>>>,
+    fuse: Option<Box<RouteTree<T>>>,
+    payload: Option<(T, Vec<MaybeUtf8Owned>)>,
+}
+
+impl<T> RouteTree<T> {
+    ///Create an empty route tree.
+    pub fn new() -> RouteTree<T> {
+        RouteTree {
+            literal: HashMap::new(),
+            keep: None,
+            fuse: None,
+            payload: None,
+        }
+    }
+
+    ///Insert `payload` at the node described by `pattern`, recording the
+    ///ordered names of the variables the pattern captures along the
+    ///way. Inserting the same pattern twice overwrites the previous
+    ///payload.
+    ///
+    ///```rust
+    ///# use rustful::handler::routing::{RouteState, RouteToken, RouteTree};
+    ///# use rustful::context::MaybeUtf8Owned;
+    ///fn name(s: &str) -> MaybeUtf8Owned {
+    ///    s.as_bytes().to_owned().into()
+    ///}
+    ///
+    ///let mut tree = RouteTree::new();
+    ///tree.insert(&[
+    ///    RouteToken::Literal(name("user")),
+    ///    RouteToken::Variable(name("id")),
+    ///], "get_user");
+    ///tree.insert(&[
+    ///    RouteToken::Literal(name("static")),
+    ///    RouteToken::Fuse(name("file")),
+    ///], "get_static");
+    ///
+    ///let mut state = RouteState::from("/user/42");
+    ///let (handler, names) = tree.find(&mut state).expect("a match");
+    ///assert_eq!(*handler, "get_user");
+    ///
+    ///let values = state.variables(names);
+    ///assert_eq!(values.get(&name("id")).map(|v| v.as_bytes()), Some(&b"42"[..]));
+    ///
+    /////a fused variable followed by another captured variable doesn't
+    /////shift that later variable's name out of alignment
+    ///tree.insert(&[
+    ///    RouteToken::Literal(name("files")),
+    ///    RouteToken::Variable(name("path")),
+    ///    RouteToken::Fuse(name("path")),
+    ///    RouteToken::Variable(name("ext")),
+    ///], "get_file");
+    ///
+    ///let mut state = RouteState::from("/files/a/b/txt");
+    ///let (handler, names) = tree.find(&mut state).expect("a match");
+    ///assert_eq!(*handler, "get_file");
+    ///
+    ///let values = state.variables(names);
+    ///assert_eq!(values.get(&name("path")).map(|v| v.as_bytes()), Some(&b"a/b"[..]));
+    ///assert_eq!(values.get(&name("ext")).map(|v| v.as_bytes()), Some(&b"txt"[..]));
+    ///```
+    pub fn insert(&mut self, pattern: &[RouteToken], payload: T) {
+        let mut names = Vec::new();
+        let mut node = self;
+        //Whether the previous token was a capture that a `Fuse` here
+        //would continue, mirroring the index reuse in `RouteState::fuse`.
+        let mut continues_capture = false;
+
+        for token in pattern {
+            node = match *token {
+                RouteToken::Literal(ref segment) => {
+                    continues_capture = false;
+                    node.literal.entry(segment.as_bytes().to_owned()).or_insert_with(RouteTree::new)
+                },
+                RouteToken::Variable(ref name) => {
+                    names.push(name.clone());
+                    continues_capture = true;
+                    &mut **node.keep.get_or_insert_with(|| Box::new(RouteTree::new()))
+                },
+                RouteToken::Fuse(ref name) => {
+                    if !continues_capture {
+                        names.push(name.clone());
+                    }
+                    continues_capture = true;
+                    &mut **node.fuse.get_or_insert_with(|| Box::new(RouteTree::new()))
+                }
+            };
+        }
+
+        node.payload = Some((payload, names));
+    }
+
+    ///Descend the tree one segment at a time, following `state`, and
+    ///return the payload and ordered variable names of the most
+    ///specific matching route, if any. A greedy `fuse` capture backs
+    ///off one segment at a time when its child doesn't match, so a
+    ///pattern like "a fused capture followed by a literal" can still
+    ///match.
+    ///
+    ///```rust
+    ///# use rustful::handler::routing::{RouteState, RouteToken, RouteTree};
+    ///# use rustful::context::MaybeUtf8Owned;
+    ///fn name(s: &str) -> MaybeUtf8Owned {
+    ///    s.as_bytes().to_owned().into()
+    ///}
+    ///
+    ///let mut tree = RouteTree::new();
+    ///tree.insert(&[
+    ///    RouteToken::Literal(name("files")),
+    ///    RouteToken::Variable(name("path")),
+    ///    RouteToken::Fuse(name("path")),
+    ///    RouteToken::Literal(name("edit")),
+    ///], "edit_file");
+    ///
+    ///let mut state = RouteState::from("/files/a/b/edit");
+    ///let (handler, names) = tree.find(&mut state).expect("a match");
+    ///assert_eq!(*handler, "edit_file");
+    ///
+    ///let values = state.variables(names);
+    ///assert_eq!(values.get(&name("path")).map(|v| v.as_bytes()), Some(&b"a/b"[..]));
+    ///```
+    pub fn find<'a>(&self, state: &mut RouteState<'a>) -> Option<(&T, &[MaybeUtf8Owned])> {
+        if state.is_empty() {
+            return self.payload.as_ref().map(|&(ref payload, ref names)| (payload, &names[..]));
+        }
+
+        let snapshot = state.snapshot();
+
+        if let Some(segment) = state.get() {
+            if let Some(child) = self.literal.get(&*segment) {
+                state.skip();
+
+                if let Some(found) = child.find(state) {
+                    return Some(found);
+                }
+
+                state.go_to(snapshot);
+            }
+        }
+
+        if let Some(ref child) = self.keep {
+            state.keep();
+
+            if let Some(found) = child.find(state) {
+                return Some(found);
+            }
+
+            state.go_to(snapshot);
+        }
+
+        if let Some(ref child) = self.fuse {
+            //Greedily fuse every remaining segment first, then back off
+            //one segment at a time, so a fuse child with its own
+            //literal children (a trailing fixed segment after the
+            //capture) still gets a chance to match instead of having
+            //all its segments swallowed up front.
+            let remaining = state.route.len() - state.index;
+
+            for take in (1..remaining + 1).rev() {
+                state.go_to(snapshot);
+
+                for _ in 0..take {
+                    state.fuse();
+                }
+
+                if let Some(found) = child.find(state) {
+                    return Some(found);
+                }
+            }
+
+            state.go_to(snapshot);
+        }
+
+        None
     }
 }